@@ -0,0 +1,226 @@
+//! Automatic texture atlas generation from loose sprite PNGs via MaxRects bin-packing.
+
+use glam::UVec2;
+use pz_pack::image::{Rgba, RgbaImage, GenericImage, GenericImageView};
+use pz_pack::image::imageops::crop_imm;
+
+/// A sprite placed into an atlas by [`build_atlas`].
+pub struct PackedSprite {
+  pub name: String,
+  /// Position of the (possibly trimmed) sprite within the atlas.
+  pub pos: UVec2,
+  /// Size of the (possibly trimmed) sprite within the atlas.
+  pub size: UVec2,
+  /// Offset of `pos`/`size` within the sprite's original, untrimmed bounds.
+  pub frame_offset: UVec2,
+  /// The sprite's original, untrimmed size.
+  pub frame_size: UVec2
+}
+
+/// Trims fully-transparent borders off of every sprite, then packs them into as small
+/// a roughly square atlas as possible, returning the composited atlas and each sprite's placement.
+pub fn build_atlas(sprites: Vec<(String, RgbaImage)>) -> (RgbaImage, Vec<PackedSprite>) {
+  let trimmed = sprites.into_iter()
+    .map(|(name, image)| (name, trim_transparent_border(&image)))
+    .collect::<Vec<(String, TrimmedSprite)>>();
+  let sizes = trimmed.iter()
+    .map(|(_, sprite)| UVec2::from(sprite.image.dimensions()))
+    .collect::<Vec<UVec2>>();
+
+  let pack_result = pack_rects(&sizes);
+
+  let mut atlas = RgbaImage::from_pixel(pack_result.bin_size.x, pack_result.bin_size.y, Rgba([0; 4]));
+  let packed_sprites = pack_result.placements.into_iter()
+    .map(|placement| {
+      let (name, sprite) = &trimmed[placement.index];
+      atlas.copy_from(&sprite.image, placement.pos.x, placement.pos.y).unwrap();
+      PackedSprite {
+        name: name.clone(),
+        pos: placement.pos,
+        size: UVec2::from(sprite.image.dimensions()),
+        frame_offset: sprite.offset,
+        frame_size: sprite.original_size
+      }
+    })
+    .collect();
+
+  (atlas, packed_sprites)
+}
+
+struct TrimmedSprite {
+  image: RgbaImage,
+  offset: UVec2,
+  original_size: UVec2
+}
+
+/// Crops away any fully-transparent border around a sprite's visible pixels.
+fn trim_transparent_border(image: &RgbaImage) -> TrimmedSprite {
+  let original_size = UVec2::from(image.dimensions());
+
+  let mut min = original_size;
+  let mut max = UVec2::ZERO;
+  for (x, y, pixel) in image.enumerate_pixels() {
+    if pixel.0[3] != 0 {
+      min = min.min(UVec2::new(x, y));
+      max = max.max(UVec2::new(x + 1, y + 1));
+    };
+  };
+
+  if min.cmpge(max).any() {
+    // The sprite is fully transparent; keep a single pixel so it still round-trips.
+    let image = RgbaImage::from_pixel(1, 1, Rgba([0; 4]));
+    return TrimmedSprite { image, offset: UVec2::ZERO, original_size };
+  };
+
+  let image = crop_imm(image, min.x, min.y, max.x - min.x, max.y - min.y).to_image();
+  TrimmedSprite { image, offset: min, original_size }
+}
+
+/// A sprite placed by [`pack_rects`], at `pos` within the bin.
+struct Placement {
+  index: usize,
+  pos: UVec2
+}
+
+struct PackResult {
+  bin_size: UVec2,
+  placements: Vec<Placement>
+}
+
+/// Packs `sizes` into as small a roughly square bin as possible using the MaxRects
+/// Best-Short-Side-Fit heuristic, growing the bin in power-of-two steps (one axis at
+/// a time) until every rectangle fits.
+fn pack_rects(sizes: &[UVec2]) -> PackResult {
+  let mut order = (0..sizes.len()).collect::<Vec<usize>>();
+  order.sort_by_key(|&index| std::cmp::Reverse(sizes[index].x * sizes[index].y));
+
+  let max_dim = sizes.iter().map(|size| size.x.max(size.y)).max().unwrap_or(1);
+  let mut bin_size = UVec2::splat(max_dim.next_power_of_two().max(64));
+
+  loop {
+    if let Some(placements) = try_pack(sizes, &order, bin_size) {
+      return PackResult { bin_size, placements };
+    };
+
+    // Grow the shorter axis first to keep the bin roughly square.
+    if bin_size.x <= bin_size.y {
+      bin_size.x *= 2;
+    } else {
+      bin_size.y *= 2;
+    };
+  };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rect {
+  pos: UVec2,
+  size: UVec2
+}
+
+impl Rect {
+  fn contains(&self, other: &Rect) -> bool {
+    other.pos.cmpge(self.pos).all() && (other.pos + other.size).cmple(self.pos + self.size).all()
+  }
+
+  fn intersects(&self, other: &Rect) -> bool {
+    self.pos.cmplt(other.pos + other.size).all() && (self.pos + self.size).cmpgt(other.pos).all()
+  }
+}
+
+fn try_pack(sizes: &[UVec2], order: &[usize], bin_size: UVec2) -> Option<Vec<Placement>> {
+  let mut free_rects = vec![Rect { pos: UVec2::ZERO, size: bin_size }];
+  let mut placements = Vec::with_capacity(order.len());
+
+  for &index in order {
+    let size = sizes[index];
+
+    // Best-Short-Side-Fit: minimize the shorter leftover edge, ties broken by the longer one.
+    let best = free_rects.iter()
+      .filter(|free_rect| size.cmple(free_rect.size).all())
+      .map(|free_rect| {
+        let leftover = free_rect.size - size;
+        let (short, long) = (leftover.x.min(leftover.y), leftover.x.max(leftover.y));
+        (short, long, Rect { pos: free_rect.pos, size })
+      })
+      .min_by_key(|&(short, long, _)| (short, long))?;
+    let placement = best.2;
+
+    placements.push(Placement { index, pos: placement.pos });
+
+    let mut next_free_rects = Vec::with_capacity(free_rects.len());
+    for free_rect in free_rects.iter() {
+      if !free_rect.intersects(&placement) {
+        next_free_rects.push(*free_rect);
+        continue;
+      };
+
+      if placement.pos.x > free_rect.pos.x {
+        next_free_rects.push(Rect {
+          pos: free_rect.pos,
+          size: UVec2::new(placement.pos.x - free_rect.pos.x, free_rect.size.y)
+        });
+      };
+      if placement.pos.x + placement.size.x < free_rect.pos.x + free_rect.size.x {
+        next_free_rects.push(Rect {
+          pos: UVec2::new(placement.pos.x + placement.size.x, free_rect.pos.y),
+          size: UVec2::new((free_rect.pos.x + free_rect.size.x) - (placement.pos.x + placement.size.x), free_rect.size.y)
+        });
+      };
+      if placement.pos.y > free_rect.pos.y {
+        next_free_rects.push(Rect {
+          pos: free_rect.pos,
+          size: UVec2::new(free_rect.size.x, placement.pos.y - free_rect.pos.y)
+        });
+      };
+      if placement.pos.y + placement.size.y < free_rect.pos.y + free_rect.size.y {
+        next_free_rects.push(Rect {
+          pos: UVec2::new(free_rect.pos.x, placement.pos.y + placement.size.y),
+          size: UVec2::new(free_rect.size.x, (free_rect.pos.y + free_rect.size.y) - (placement.pos.y + placement.size.y))
+        });
+      };
+    };
+
+    // Collapse exact duplicates down to one copy each; `Rect::contains` is non-strict,
+    // so two identical free rects would otherwise "contain" each other and both get
+    // pruned below, leaking usable free space.
+    let mut deduped_free_rects: Vec<Rect> = Vec::with_capacity(next_free_rects.len());
+    for rect in next_free_rects.iter() {
+      if !deduped_free_rects.contains(rect) {
+        deduped_free_rects.push(*rect);
+      };
+    };
+
+    // Prune any free rect fully contained within another.
+    free_rects = deduped_free_rects.iter().enumerate()
+      .filter(|&(i, rect)| !deduped_free_rects.iter().enumerate().any(|(j, other)| i != j && other.contains(rect)))
+      .map(|(_, &rect)| rect)
+      .collect();
+  };
+
+  Some(placements)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pack_rects_tiles_identical_sprites_without_growing_bin() {
+    // A 4x4 grid of identical squares exactly fills the default 64x64 starting bin;
+    // lost free space from the duplicate-free-rect pruning bug would force the bin
+    // to grow unnecessarily instead.
+    let sizes = vec![UVec2::new(16, 16); 16];
+    let result = pack_rects(&sizes);
+
+    assert_eq!(result.bin_size, UVec2::new(64, 64));
+    assert_eq!(result.placements.len(), 16);
+
+    for (i, a) in result.placements.iter().enumerate() {
+      let rect_a = Rect { pos: a.pos, size: sizes[a.index] };
+      for b in &result.placements[i + 1..] {
+        let rect_b = Rect { pos: b.pos, size: sizes[b.index] };
+        assert!(!rect_a.intersects(&rect_b), "placements for sprites {} and {} overlap", a.index, b.index);
+      };
+    };
+  }
+}
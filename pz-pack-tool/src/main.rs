@@ -1,6 +1,7 @@
 extern crate clap;
 extern crate defy;
 extern crate glam;
+extern crate indicatif;
 extern crate pz_pack;
 extern crate serde;
 #[macro_use]
@@ -18,10 +19,15 @@ use serde::{Deserialize, Serialize};
 
 use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
-use std::io::{self, BufReader, BufWriter};
+use std::io::{self, BufReader, BufWriter, Write};
 use std::ffi::OsStr;
 use std::fs::File;
 
+mod atlas;
+mod split;
+
+use split::SplitWriter;
+
 
 
 #[derive(Debug, Parser)]
@@ -37,7 +43,20 @@ enum Cli {
     in_path: PathBuf,
     /// The path to the destination for the produced pack file to be placed.
     #[arg(id = "out")]
-    out_path: PathBuf
+    out_path: PathBuf,
+    /// Compress the pack with the given codec, saving it as a "V3" pack file.
+    #[arg(long)]
+    compress: Option<CliCodec>,
+    /// Compression level to use with `--compress` (codec-specific, defaults to a sane value).
+    #[arg(long)]
+    level: Option<i32>,
+    /// Split the pack into numbered parts (`<out>.000`, `<out>.001`, ...) no larger
+    /// than this many bytes each. Accepts human units, e.g. `64MiB`.
+    #[arg(long, value_parser = parse_byte_size)]
+    split_size: Option<u64>,
+    /// Suppress the progress bar.
+    #[arg(long)]
+    quiet: bool
   },
   /// Unpacks a given .pack file into a directory.
   Unpack {
@@ -46,7 +65,10 @@ enum Cli {
     in_path: PathBuf,
     /// The path to the destination for the produced directory to be placed.
     #[arg(id = "out")]
-    out_path: PathBuf
+    out_path: PathBuf,
+    /// Suppress the progress bar.
+    #[arg(long)]
+    quiet: bool
   },
   /// Unpacks a given page from a given .pack file into a directory.
   UnpackPage {
@@ -59,14 +81,43 @@ enum Cli {
     /// The page who's entries should be extracted.
     #[arg(id = "page")]
     page_name: String
+  },
+  /// Checks a .pack file for corruption without writing anything to disk.
+  Verify {
+    /// The path to the pack file to verify.
+    #[arg(id = "in")]
+    in_path: PathBuf
+  },
+  /// Lists the pages of a .pack file without decoding any images.
+  Info {
+    /// The path to the pack file to inspect.
+    #[arg(id = "in")]
+    in_path: PathBuf
+  },
+  /// Packs a directory of loose sprite .png files into a single atlas image and a
+  /// matching page .toml, auto-placing sprites with a MaxRects bin-packer.
+  BuildPage {
+    /// The path to the directory of loose sprite .png files.
+    #[arg(id = "in")]
+    in_dir: PathBuf,
+    /// The path to the destination for the produced atlas image.
+    #[arg(id = "out-png")]
+    out_png: PathBuf,
+    /// The path to the destination for the produced page config.
+    #[arg(id = "out-toml")]
+    out_toml: PathBuf
   }
 }
 
 fn main() {
   let result = match Cli::parse() {
-    Cli::Pack { in_path, out_path } => pack(in_path, out_path),
-    Cli::Unpack { in_path, out_path } => unpack(in_path, out_path),
-    Cli::UnpackPage { in_path, out_path, page_name } => unpack_page(in_path, out_path, page_name)
+    Cli::Pack { in_path, out_path, compress, level, split_size, quiet } =>
+      pack(in_path, out_path, compress, level, split_size, quiet),
+    Cli::Unpack { in_path, out_path, quiet } => unpack(in_path, out_path, quiet),
+    Cli::UnpackPage { in_path, out_path, page_name } => unpack_page(in_path, out_path, page_name),
+    Cli::Verify { in_path } => verify(in_path),
+    Cli::Info { in_path } => info(in_path),
+    Cli::BuildPage { in_dir, out_png, out_toml } => build_page(in_dir, out_png, out_toml)
   };
 
   if let Err(error) = result {
@@ -74,6 +125,68 @@ fn main() {
   };
 }
 
+/// Compression codec accepted by the `--compress` flag on the `pack` subcommand.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum CliCodec {
+  Zstd,
+  Lzma,
+  Bzip2
+}
+
+impl CliCodec {
+  fn into_codec(self, level: Option<i32>) -> pz_pack::Codec {
+    match self {
+      CliCodec::Zstd => pz_pack::Codec::Zstd { level: level.unwrap_or(3) },
+      CliCodec::Lzma => pz_pack::Codec::Lzma { level: level.unwrap_or(6) as u32 },
+      CliCodec::Bzip2 => pz_pack::Codec::Bzip2 { level: level.unwrap_or(6) as u32 }
+    }
+  }
+}
+
+/// Builds a spinner-style progress bar reporting a [`pz_pack::ProgressEvent`] stream,
+/// or `None` if `quiet` is set, in which case progress should simply not be reported.
+fn make_progress_bar(quiet: bool) -> Option<indicatif::ProgressBar> {
+  if quiet {
+    return None;
+  };
+
+  let bar = indicatif::ProgressBar::new(0);
+  if let Ok(style) = indicatif::ProgressStyle::with_template("{spinner} [{pos}/{len}] {msg}") {
+    bar.set_style(style);
+  };
+
+  Some(bar)
+}
+
+fn report_progress(bar: &Option<indicatif::ProgressBar>, event: pz_pack::ProgressEvent) {
+  let Some(bar) = bar else { return };
+  bar.set_length(event.page_count as u64);
+  bar.set_position(event.page_index as u64);
+  bar.set_message(format!("{} ({:?})", event.page_name, event.stage));
+  bar.tick();
+}
+
+/// Parses a byte size accepting an optional binary unit suffix (`B`, `KiB`, `MiB`, `GiB`),
+/// for use with the `--split-size` flag.
+fn parse_byte_size(input: &str) -> Result<u64, String> {
+  let input = input.trim();
+  let split_at = input.find(|c: char| !c.is_ascii_digit() && c != '.')
+    .unwrap_or(input.len());
+  let (number, unit) = input.split_at(split_at);
+
+  let number = number.parse::<f64>()
+    .map_err(|_| format!("{input:?} is not a valid size"))?;
+  let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+    "" | "b" => 1u64,
+    "k" | "kib" => 1024,
+    "m" | "mib" => 1024 * 1024,
+    "g" | "gib" => 1024 * 1024 * 1024,
+    unit => return Err(format!("{unit:?} is not a recognized size unit"))
+  };
+
+  Ok((number * multiplier as f64) as u64)
+}
+
 #[derive(Debug, Error)]
 enum Error {
   #[error("sub-image too small ({1} > {2}) for entry {0}")]
@@ -95,7 +208,7 @@ enum Error {
 }
 
 fn unpack_page(in_path: PathBuf, out_path: PathBuf, page_name: String) -> Result<(), Error> {
-  let pack_file = File::open(&in_path).map(BufReader::new)
+  let pack_file = split::open_pack_reader(&in_path)
     .context_path("failed to open pack file", &in_path)?;
   let pack = Pack::read(pack_file)
     .context_path("failed to read pack file", &in_path)?;
@@ -123,12 +236,90 @@ fn unpack_page(in_path: PathBuf, out_path: PathBuf, page_name: String) -> Result
   Ok(())
 }
 
-fn unpack(in_path: PathBuf, out_path: PathBuf) -> Result<(), Error> {
+fn verify(in_path: PathBuf) -> Result<(), Error> {
   let pack_file = File::open(&in_path).map(BufReader::new)
     .context_path("failed to open pack file", &in_path)?;
-  let pack = Pack::read(pack_file)
+  // Each page's checksum/PNG is validated independently, so a corrupt page doesn't
+  // prevent every other page in the pack from being reported on.
+  let pages = Pack::read_report(pack_file)
     .context_path("failed to read pack file", &in_path)?;
 
+  let mut all_ok = true;
+  for (page_index, page_result) in pages.into_iter().enumerate() {
+    let page = match page_result {
+      Ok(page) => page,
+      Err(error) => {
+        eprintln!("  {error}");
+        println!("FAIL page {page_index}");
+        all_ok = false;
+        continue;
+      }
+    };
+
+    let mut page_ok = true;
+    for entry in page.entries.iter() {
+      if let Err(error) = check_entry_bounds(entry, &page.image) {
+        eprintln!("  {}: {error}", entry.name);
+        page_ok = false;
+      };
+    };
+
+    println!("{} {}", if page_ok { "ok  " } else { "FAIL" }, page.name);
+    all_ok &= page_ok;
+  };
+
+  if !all_ok {
+    std::process::exit(1);
+  };
+
+  Ok(())
+}
+
+fn check_entry_bounds(entry: &Entry, image: &RgbaImage) -> Result<(), Error> {
+  let pos = UVec2::new(entry.x_pos, entry.y_pos);
+  let size = UVec2::new(entry.width, entry.height);
+  let frame_offset = UVec2::new(entry.x_offset, entry.y_offset);
+  let frame_size = UVec2::new(entry.total_width, entry.total_height);
+  let image_size = UVec2::from(image.dimensions());
+
+  if size == UVec2::ZERO || image_size.cmplt(pos + size).any() {
+    return Err(Error::SubImageTooBig(entry.name.clone(), size, image_size));
+  };
+
+  if frame_size == UVec2::ZERO || size.cmpgt(frame_offset + frame_size).any() {
+    return Err(Error::FrameTooSmall(entry.name.clone(), frame_size, size));
+  };
+
+  Ok(())
+}
+
+fn info(in_path: PathBuf) -> Result<(), Error> {
+  let pack_file = File::open(&in_path).map(BufReader::new)
+    .context_path("failed to open pack file", &in_path)?;
+  let index = Pack::read_index(pack_file)
+    .context_path("failed to read pack file", &in_path)?;
+
+  for page in index.pages.iter() {
+    println!(
+      "{} ({}x{}, {} entries, {} bytes)",
+      page.name, page.width, page.height, page.entries.len(), page.image_len
+    );
+  };
+
+  Ok(())
+}
+
+fn unpack(in_path: PathBuf, out_path: PathBuf, quiet: bool) -> Result<(), Error> {
+  let pack_file = split::open_pack_reader(&in_path)
+    .context_path("failed to open pack file", &in_path)?;
+
+  let bar = make_progress_bar(quiet);
+  let pack = Pack::read_with_progress(pack_file, |event| report_progress(&bar, event))
+    .context_path("failed to read pack file", &in_path)?;
+  if let Some(bar) = bar {
+    bar.finish_and_clear();
+  };
+
   let mut dir_created = false;
   for page in pack.pages {
     if !dir_created {
@@ -155,7 +346,10 @@ fn unpack(in_path: PathBuf, out_path: PathBuf) -> Result<(), Error> {
   Ok(())
 }
 
-fn pack(in_path: PathBuf, out_path: PathBuf) -> Result<(), Error> {
+fn pack(
+  in_path: PathBuf, out_path: PathBuf,
+  compress: Option<CliCodec>, level: Option<i32>, split_size: Option<u64>, quiet: bool
+) -> Result<(), Error> {
   let mut toml_files = HashMap::new();
   let mut png_files = HashMap::new();
   for result in std::fs::read_dir(&in_path).context_path("failed to read dir", &in_path)? {
@@ -195,9 +389,78 @@ fn pack(in_path: PathBuf, out_path: PathBuf) -> Result<(), Error> {
   };
 
   let pack = Pack::new(pages);
-  let writer = File::create(&out_path).map(BufWriter::new)
-    .context_path("failed to create pack", &out_path)?;
-  pack.write(writer).context_path("failed to write pack", &out_path)?;
+  let bar = make_progress_bar(quiet);
+  let write_result = match split_size {
+    Some(part_size) => {
+      let writer = SplitWriter::create(out_path.clone(), part_size)
+        .context_path("failed to create pack", &out_path)?;
+      write_pack(&pack, writer, compress, level, &bar)
+    },
+    None => {
+      let writer = File::create(&out_path).map(BufWriter::new)
+        .context_path("failed to create pack", &out_path)?;
+      write_pack(&pack, writer, compress, level, &bar)
+    }
+  };
+  if let Some(bar) = bar {
+    bar.finish_and_clear();
+  };
+  write_result.context_path("failed to write pack", &out_path)?;
+
+  Ok(())
+}
+
+fn write_pack<W: Write>(
+  pack: &Pack, writer: W,
+  compress: Option<CliCodec>, level: Option<i32>, bar: &Option<indicatif::ProgressBar>
+) -> Result<(), pz_pack::Error> {
+  match compress {
+    // Compressed packs are written in one shot, so progress isn't reported for them.
+    Some(codec) => pack.write_with(writer, pz_pack::FormatVersion::V3, codec.into_codec(level)),
+    None => pack.write_with_progress(writer, |event| report_progress(bar, event))
+  }
+}
+
+fn build_page(in_dir: PathBuf, out_png: PathBuf, out_toml: PathBuf) -> Result<(), Error> {
+  let mut sprites = Vec::new();
+  for result in std::fs::read_dir(&in_dir).context_path("failed to read dir", &in_dir)? {
+    let entry = result.context_path("failed to read dir entry", &in_dir)?;
+    let file_type = entry.file_type().context_path("failed to read dir entry file type", &in_dir)?;
+    if !file_type.is_file() { continue };
+
+    let path = entry.path();
+    let Some(stem) = path.file_stem().and_then(OsStr::to_str) else { continue };
+    let is_png = path.extension().and_then(OsStr::to_str).is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    if !is_png { continue };
+
+    let png_reader = File::open(&path).map(BufReader::new)
+      .context_path("failed to open image file", &path)?;
+    let image = pz_pack::read_png(png_reader)
+      .context_path("failed to read image file", &path)?;
+
+    sprites.push((stem.to_owned(), image));
+  };
+
+  let (atlas, packed_sprites) = atlas::build_atlas(sprites);
+
+  let entries = packed_sprites.into_iter()
+    .map(|sprite| {
+      let frame = (sprite.frame_offset != UVec2::ZERO || sprite.frame_size != sprite.size)
+        .then_some(EntryConfigFrame { offset: sprite.frame_offset, size: sprite.frame_size });
+      (sprite.name, EntryConfig { pos: sprite.pos, size: sprite.size, frame })
+    })
+    .collect();
+  let page_config = PageConfig { entries };
+
+  let toml_buf = toml::to_string_pretty(&page_config)
+    .context("failed to serialize page")?;
+  std::fs::write(&out_toml, &toml_buf)
+    .context_path("failed to write page", &out_toml)?;
+
+  let png_writer = File::create(&out_png).map(BufWriter::new)
+    .context_path("failed to create image", &out_png)?;
+  pz_pack::write_png(png_writer, &atlas)
+    .context_path("failed to write image", &out_png)?;
 
   Ok(())
 }
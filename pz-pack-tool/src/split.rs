@@ -0,0 +1,125 @@
+//! Stream adapters for splitting a pack file across multiple numbered parts
+//! (`name.pack.000`, `name.pack.001`, ...) and reading them back transparently.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+
+
+/// A [`Write`] adapter that rolls over to a new numbered part file once the
+/// current part reaches `part_size` bytes.
+pub struct SplitWriter {
+  base_path: PathBuf,
+  part_size: u64,
+  part_index: u32,
+  written_in_part: u64,
+  current: BufWriter<File>
+}
+
+impl SplitWriter {
+  pub fn create(base_path: PathBuf, part_size: u64) -> io::Result<Self> {
+    let current = File::create(part_path(&base_path, 0)).map(BufWriter::new)?;
+    Ok(SplitWriter { base_path, part_size, part_index: 0, written_in_part: 0, current })
+  }
+
+  fn roll_over(&mut self) -> io::Result<()> {
+    self.current.flush()?;
+    self.part_index += 1;
+    self.written_in_part = 0;
+    self.current = File::create(part_path(&self.base_path, self.part_index)).map(BufWriter::new)?;
+    Ok(())
+  }
+}
+
+impl Write for SplitWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+      return Ok(0);
+    };
+
+    if self.written_in_part >= self.part_size {
+      self.roll_over()?;
+    };
+
+    let len = (buf.len() as u64).min(self.part_size - self.written_in_part) as usize;
+    let written = self.current.write(&buf[..len])?;
+    self.written_in_part += written as u64;
+    Ok(written)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.current.flush()
+  }
+}
+
+/// A [`Read`] adapter that concatenates the numbered parts of a split pack file in order.
+pub struct SplitReader {
+  base_path: PathBuf,
+  part_index: u32,
+  current: BufReader<File>
+}
+
+impl SplitReader {
+  /// Opens a split pack file, given the path to its first part (ending in `.000`).
+  pub fn open(first_part_path: PathBuf) -> io::Result<Self> {
+    let base_path = first_part_path.with_extension("");
+    let current = File::open(&first_part_path).map(BufReader::new)?;
+    Ok(SplitReader { base_path, part_index: 0, current })
+  }
+}
+
+impl Read for SplitReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+      let n = self.current.read(buf)?;
+      if n > 0 {
+        return Ok(n);
+      };
+
+      let next_part_path = part_path(&self.base_path, self.part_index + 1);
+      if !next_part_path.is_file() {
+        return Ok(0);
+      };
+
+      self.part_index += 1;
+      self.current = File::open(&next_part_path).map(BufReader::new)?;
+    };
+  }
+}
+
+/// Either a plain file reader or a [`SplitReader`], chosen by [`open_pack_reader`].
+pub enum PackReader {
+  Plain(BufReader<File>),
+  Split(SplitReader)
+}
+
+impl Read for PackReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      PackReader::Plain(reader) => reader.read(buf),
+      PackReader::Split(reader) => reader.read(buf)
+    }
+  }
+}
+
+/// Opens `path` for reading, transparently detecting a split pack file: either
+/// `path` itself ends in `.000`, or a `.000` sibling of `path` exists.
+pub fn open_pack_reader(path: &Path) -> io::Result<PackReader> {
+  if path.extension().is_some_and(|ext| ext == "000") {
+    return SplitReader::open(path.to_owned()).map(PackReader::Split);
+  };
+
+  let first_part_path = part_path(path, 0);
+  if first_part_path.is_file() {
+    return SplitReader::open(first_part_path).map(PackReader::Split);
+  };
+
+  File::open(path).map(BufReader::new).map(PackReader::Plain)
+}
+
+fn part_path(base_path: &Path, index: u32) -> PathBuf {
+  let mut os_string = base_path.as_os_str().to_owned();
+  os_string.push(format!(".{index:03}"));
+  PathBuf::from(os_string)
+}
@@ -3,18 +3,34 @@ extern crate defy;
 pub extern crate image;
 #[macro_use]
 extern crate thiserror;
+extern crate zstd;
+extern crate crc32fast;
+#[cfg(feature = "lzma")]
+extern crate xz2;
+#[cfg(feature = "bzip2")]
+extern crate bzip2;
 
 use byteorder::{LE, ReadBytesExt, WriteBytesExt};
 use defy::{ContextualError, Contextualize};
 use image::{Rgba, RgbaImage, ImageFormat, GenericImage};
 use image::imageops::crop_imm;
 
-use std::io::{self, Cursor, Read, Write};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, Write};
 
 #[derive(Debug, Error)]
 pub enum Error {
   #[error("unsupported image format {0:?}")]
   UnsupportedImageFormat(ImageFormat),
+  #[error("unknown pack compression codec tag {0}")]
+  UnknownCodec(u8),
+  #[error("pack uses the {0:?} codec, but support for it was not compiled in (enable the `{1}` feature)")]
+  CodecNotEnabled(Codec, &'static str),
+  #[error("checksum mismatch for page {page:?} (expected {expected:#010x}, got {actual:#010x})")]
+  ChecksumMismatch { page: String, expected: u32, actual: u32 },
+  #[error("pack uses a compressed codec, which does not support lazy/seek-based reading")]
+  CompressedPackNotSeekable,
+  #[error("{0:?} pack files do not support lazy/seek-based reading")]
+  LazyReadUnsupported(FormatVersion),
   #[error(transparent)]
   Io(#[from] ContextualError<io::Error>),
   #[error(transparent)]
@@ -22,6 +38,7 @@ pub enum Error {
 }
 
 const MAGIC_BYTES: [u8; 4] = *b"PZPK";
+const MAGIC_BYTES_V3: [u8; 4] = *b"PZP3";
 const END_OF_IMAGE: [u8; 4] = u32::to_le_bytes(0xDEADBEEF);
 
 /// An entry within a page.
@@ -102,7 +119,7 @@ impl Page {
     self.entries.get(index).map(|entry| entry.get_image(&self.image))
   }
 
-  fn read_v1<R: Read>(mut reader: R) -> Result<Self, Error> {
+  fn read_v1<R: BufRead>(mut reader: R) -> Result<Self, Error> {
     let name = read_string(&mut reader)
       .context("failed to read name for page")?;
     let entries_len = reader.read_u32::<LE>()
@@ -190,6 +207,90 @@ impl Page {
 
     Ok(())
   }
+
+  fn read_v3<R: Read>(mut reader: R) -> Result<Self, Error> {
+    let mut hasher = crc32fast::Hasher::new();
+    let page = Page::read_v2(HashingReader::new(&mut reader, &mut hasher));
+
+    // The checksum trails the page even when its image fails to decode, so it is read
+    // before returning that error, leaving `reader` positioned at the next page.
+    let expected = hasher.finalize();
+    let actual = reader.read_u32::<LE>()
+      .context("failed to read checksum for page");
+    let page = page?;
+    let actual = actual?;
+    if expected != actual {
+      return Err(Error::ChecksumMismatch { page: page.name, expected, actual });
+    };
+
+    Ok(page)
+  }
+
+  fn write_v3<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+    let mut body = Cursor::new(Vec::new());
+    self.write_v2(&mut body)?;
+    let body = body.into_inner();
+
+    let checksum = crc32fast::hash(&body);
+    writer.write_all(&body)
+      .context("failed to write page")?;
+    writer.write_u32::<LE>(checksum)
+      .context("failed to write checksum for page")?;
+
+    Ok(())
+  }
+
+  fn read_v2_with_progress<R: Read, F: FnMut(ProgressEvent)>(
+    mut reader: R, page_index: usize, page_count: usize, on_progress: &mut F
+  ) -> Result<Self, Error> {
+    let name = read_string(&mut reader)
+      .context("failed to read name for page")?;
+    on_progress(ProgressEvent { page_index, page_count, page_name: name.clone(), stage: ProgressStage::Reading });
+
+    let entries_len = reader.read_u32::<LE>()
+      .context("failed to read entries_len for page")?;
+    let mask = reader.read_i32::<LE>()
+      .context("failed to read mask for page")?;
+    let entries = (0..entries_len)
+      .map(|_| Entry::read(&mut reader))
+      .collect::<Result<Vec<Entry>, Error>>()?;
+
+    let image_buf = read_buffer(&mut reader)
+      .context("failed to read image contents for page")?;
+
+    on_progress(ProgressEvent { page_index, page_count, page_name: name.clone(), stage: ProgressStage::Decoding });
+    let image = image::load_from_memory(&image_buf)
+      .context("failed to decode image contents for page")?
+      .into_rgba8();
+
+    Ok(Page { name, mask, entries, image })
+  }
+
+  fn write_v2_with_progress<W: Write, F: FnMut(ProgressEvent)>(
+    &self, mut writer: W, page_index: usize, page_count: usize, on_progress: &mut F
+  ) -> Result<(), Error> {
+    on_progress(ProgressEvent { page_index, page_count, page_name: self.name.clone(), stage: ProgressStage::Encoding });
+
+    write_string(&mut writer, &self.name)
+      .context("failed to write name for page")?;
+    writer.write_u32::<LE>(self.entries.len() as u32)
+      .context("failed to write entries_len for page")?;
+    writer.write_i32::<LE>(self.mask)
+      .context("failed to write mask for page")?;
+    for entry in self.entries.iter() {
+      entry.write(&mut writer)?;
+    };
+
+    let mut buf = Cursor::new(Vec::new());
+    write_png(&mut buf, &self.image)
+      .context("failed to encode image contents for page")?;
+
+    on_progress(ProgressEvent { page_index, page_count, page_name: self.name.clone(), stage: ProgressStage::Writing });
+    write_buffer(&mut writer, buf.get_ref())
+      .context("failed to write image contents for page")?;
+
+    Ok(())
+  }
 }
 
 /// The full contents of a pack file.
@@ -209,19 +310,147 @@ impl Pack {
     Pack { mask: Self::DEFAULT_MASK, pages }
   }
 
+  /// Reads just the page metadata of a pack file, seeking past each page's image
+  /// blob instead of decoding it. Use [`Pack::read_page_at`] to decode a page's
+  /// image afterwards, on demand.
+  ///
+  /// Only uncompressed "V2" and "V3" pack files support this; "V1" pack files (whose
+  /// images are terminated rather than length-prefixed) and compressed "V3" pack files
+  /// do not.
+  pub fn read_index<R: Read + Seek>(mut reader: R) -> Result<PackIndex, Error> {
+    let mut magic_bytes = [0; 4];
+    reader.read_exact(&mut magic_bytes)
+      .context("failed to read pack")?;
+    if magic_bytes == MAGIC_BYTES_V3 {
+      let codec_tag = reader.read_u8()
+        .context("failed to read codec tag for pack")?;
+      let _decompressed_len = reader.read_u32::<LE>()
+        .context("failed to read decompressed length for pack")?;
+      if codec_tag != Codec::TAG_NONE {
+        return Err(Error::CompressedPackNotSeekable);
+      };
+      PackIndex::read_body_v3(reader)
+    } else if magic_bytes == MAGIC_BYTES {
+      PackIndex::read_body(reader)
+    } else {
+      Err(Error::LazyReadUnsupported(FormatVersion::V1))
+    }
+  }
+
+  /// Decodes a single page's image from a pack file, given a [`PageHeader`] previously
+  /// produced by [`Pack::read_index`].
+  pub fn read_page_at<R: Read + Seek>(mut reader: R, header: &PageHeader) -> Result<RgbaImage, Error> {
+    reader.seek(io::SeekFrom::Start(header.image_offset))
+      .context("failed to seek to page image")?;
+    let image_buf = read_buffer_of_len(&mut reader, header.image_len)
+      .context("failed to read image contents for page")?;
+    let image = image::load_from_memory(&image_buf)
+      .context("failed to decode image contents for page")?
+      .into_rgba8();
+
+    Ok(image)
+  }
+
   pub fn read<R: Read>(mut reader: R) -> Result<Self, Error> {
     let mut magic_bytes = [0; 4];
     reader.read_exact(&mut magic_bytes)
       .context("failed to read pack")?;
-    if magic_bytes == MAGIC_BYTES {
+    if magic_bytes == MAGIC_BYTES_V3 {
+      Pack::read_v3(reader)
+    } else if magic_bytes == MAGIC_BYTES {
       Pack::read_v2(reader)
     } else {
-      let reader = Cursor::new(magic_bytes).chain(reader);
+      let reader = BufReader::new(Cursor::new(magic_bytes).chain(reader));
       Pack::read_v1(reader)
     }
   }
 
-  fn read_v1<R: Read>(mut reader: R) -> Result<Self, Error> {
+  /// Like [`Pack::read`], but invokes `on_progress` as each page is read, letting
+  /// callers report progress while reading large packs.
+  ///
+  /// Progress is only reported while reading a "V2" pack file; "V1" and "V3" pack
+  /// files are read in one shot with no intermediate events.
+  pub fn read_with_progress<R: Read, F: FnMut(ProgressEvent)>(mut reader: R, mut on_progress: F) -> Result<Self, Error> {
+    let mut magic_bytes = [0; 4];
+    reader.read_exact(&mut magic_bytes)
+      .context("failed to read pack")?;
+    if magic_bytes == MAGIC_BYTES_V3 {
+      Pack::read_v3(reader)
+    } else if magic_bytes == MAGIC_BYTES {
+      Pack::read_v2_with_progress(reader, &mut on_progress)
+    } else {
+      let reader = BufReader::new(Cursor::new(magic_bytes).chain(reader));
+      Pack::read_v1(reader)
+    }
+  }
+
+  /// Like [`Pack::read`], but doesn't stop at the first page that fails to parse —
+  /// each page's outcome (a decoded [`Page`], or the [`Error`] that page hit) is
+  /// reported individually, rather than the whole read short-circuiting on one bad
+  /// page. Used by the `verify` subcommand to report on every page in a pack, even
+  /// ones after the first corrupt one.
+  ///
+  /// Only "V2" and "V3" pack files support this: their pages are each
+  /// length-prefixed, so the reader stays in sync for the next page even after one
+  /// page's checksum or PNG fails to validate. "V1" pack files, whose images are
+  /// terminator-delimited rather than length-prefixed, can't resynchronize after a
+  /// bad page and so are read with [`Pack::read`] instead, as a single `Result`.
+  pub fn read_report<R: Read>(mut reader: R) -> Result<Vec<Result<Page, Error>>, Error> {
+    let mut magic_bytes = [0; 4];
+    reader.read_exact(&mut magic_bytes)
+      .context("failed to read pack")?;
+    if magic_bytes == MAGIC_BYTES_V3 {
+      Pack::read_v3_report(reader)
+    } else if magic_bytes == MAGIC_BYTES {
+      Pack::read_v2_report(reader)
+    } else {
+      let reader = BufReader::new(Cursor::new(magic_bytes).chain(reader));
+      Pack::read_v1(reader).map(|pack| pack.pages.into_iter().map(Ok).collect())
+    }
+  }
+
+  fn read_v2_report<R: Read>(mut reader: R) -> Result<Vec<Result<Page, Error>>, Error> {
+    let _mask = reader.read_i32::<LE>()
+      .context("failed to read pack")?;
+    let pages_len = reader.read_u32::<LE>()
+      .context("failed to read pages_len for pack")?;
+    Ok((0..pages_len).map(|_| Page::read_v2(&mut reader)).collect())
+  }
+
+  fn read_v3_report<R: Read>(mut reader: R) -> Result<Vec<Result<Page, Error>>, Error> {
+    let codec_tag = reader.read_u8()
+      .context("failed to read codec tag for pack")?;
+    let _decompressed_len = reader.read_u32::<LE>()
+      .context("failed to read decompressed length for pack")?;
+
+    match codec_tag {
+      Codec::TAG_NONE => Pack::read_v3_body_report(reader),
+      Codec::TAG_ZSTD => {
+        let reader = zstd::stream::read::Decoder::new(reader)
+          .context("failed to start zstd decoder")?;
+        Pack::read_v3_body_report(reader)
+      },
+      #[cfg(feature = "lzma")]
+      Codec::TAG_LZMA => Pack::read_v3_body_report(xz2::read::XzDecoder::new(reader)),
+      #[cfg(not(feature = "lzma"))]
+      Codec::TAG_LZMA => Err(Error::CodecNotEnabled(Codec::Lzma { level: 0 }, "lzma")),
+      #[cfg(feature = "bzip2")]
+      Codec::TAG_BZIP2 => Pack::read_v3_body_report(bzip2::read::BzDecoder::new(reader)),
+      #[cfg(not(feature = "bzip2"))]
+      Codec::TAG_BZIP2 => Err(Error::CodecNotEnabled(Codec::Bzip2 { level: 0 }, "bzip2")),
+      tag => Err(Error::UnknownCodec(tag))
+    }
+  }
+
+  fn read_v3_body_report<R: Read>(mut reader: R) -> Result<Vec<Result<Page, Error>>, Error> {
+    let _mask = reader.read_i32::<LE>()
+      .context("failed to read pack")?;
+    let pages_len = reader.read_u32::<LE>()
+      .context("failed to read pages_len for pack")?;
+    Ok((0..pages_len).map(|_| Page::read_v3(&mut reader)).collect())
+  }
+
+  fn read_v1<R: BufRead>(mut reader: R) -> Result<Self, Error> {
     let pages_len = reader.read_u32::<LE>()
       .context("failed to read pages_len for pack")?;
     (0..pages_len).map(|_| Page::read_v1(&mut reader))
@@ -239,19 +468,72 @@ impl Pack {
       .map(|pages| Pack { mask, pages })
   }
 
+  fn read_v2_with_progress<R: Read, F: FnMut(ProgressEvent)>(mut reader: R, on_progress: &mut F) -> Result<Self, Error> {
+    let mask = reader.read_i32::<LE>()
+      .context("failed to read pack")?;
+    let page_count = reader.read_u32::<LE>()
+      .context("failed to read pages_len for pack")? as usize;
+    (0..page_count)
+      .map(|page_index| Page::read_v2_with_progress(&mut reader, page_index, page_count, on_progress))
+      .collect::<Result<Vec<Page>, Error>>()
+      .map(|pages| Pack { mask, pages })
+  }
+
+  fn read_v3<R: Read>(mut reader: R) -> Result<Self, Error> {
+    let codec_tag = reader.read_u8()
+      .context("failed to read codec tag for pack")?;
+    let _decompressed_len = reader.read_u32::<LE>()
+      .context("failed to read decompressed length for pack")?;
+
+    match codec_tag {
+      Codec::TAG_NONE => Pack::read_v3_body(reader),
+      Codec::TAG_ZSTD => {
+        let reader = zstd::stream::read::Decoder::new(reader)
+          .context("failed to start zstd decoder")?;
+        Pack::read_v3_body(reader)
+      },
+      #[cfg(feature = "lzma")]
+      Codec::TAG_LZMA => Pack::read_v3_body(xz2::read::XzDecoder::new(reader)),
+      #[cfg(not(feature = "lzma"))]
+      Codec::TAG_LZMA => Err(Error::CodecNotEnabled(Codec::Lzma { level: 0 }, "lzma")),
+      #[cfg(feature = "bzip2")]
+      Codec::TAG_BZIP2 => Pack::read_v3_body(bzip2::read::BzDecoder::new(reader)),
+      #[cfg(not(feature = "bzip2"))]
+      Codec::TAG_BZIP2 => Err(Error::CodecNotEnabled(Codec::Bzip2 { level: 0 }, "bzip2")),
+      tag => Err(Error::UnknownCodec(tag))
+    }
+  }
+
+  fn read_v3_body<R: Read>(mut reader: R) -> Result<Self, Error> {
+    let mask = reader.read_i32::<LE>()
+      .context("failed to read pack")?;
+    let pages_len = reader.read_u32::<LE>()
+      .context("failed to read pages_len for pack")?;
+    (0..pages_len).map(|_| Page::read_v3(&mut reader))
+      .collect::<Result<Vec<Page>, Error>>()
+      .map(|pages| Pack { mask, pages })
+  }
+
   #[inline]
   pub fn write<W: Write>(&self, writer: W) -> Result<(), Error> {
     self.write_v2(writer)
   }
 
   #[inline]
-  pub fn write_with<W: Write>(&self, writer: W, version: FormatVersion) -> Result<(), Error> {
+  pub fn write_with<W: Write>(&self, writer: W, version: FormatVersion, codec: Codec) -> Result<(), Error> {
     match version {
       FormatVersion::V1 => self.write_v1(writer),
-      FormatVersion::V2 => self.write_v2(writer)
+      FormatVersion::V2 => self.write_v2(writer),
+      FormatVersion::V3 => self.write_v3(writer, codec)
     }
   }
 
+  /// Like [`Pack::write`], but invokes `on_progress` as each page is written, letting
+  /// callers report progress while writing large packs. Always writes a "V2" pack file.
+  pub fn write_with_progress<W: Write, F: FnMut(ProgressEvent)>(&self, writer: W, mut on_progress: F) -> Result<(), Error> {
+    self.write_v2_with_progress(writer, &mut on_progress)
+  }
+
   fn write_v1<W: Write>(&self, mut writer: W) -> Result<(), Error> {
     writer.write_u32::<LE>(self.pages.len() as u32)
       .context("failed to write pages_len for pack")?;
@@ -266,14 +548,27 @@ impl Pack {
   }
 
   fn write_v2<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+    writer.write_all(&MAGIC_BYTES)
+      .context("failed to write pack")?;
+    self.write_v2_body(&mut writer)?;
+
+    writer.flush()
+      .context("failed to flush writer")?;
+
+    Ok(())
+  }
+
+  fn write_v2_with_progress<W: Write, F: FnMut(ProgressEvent)>(&self, mut writer: W, on_progress: &mut F) -> Result<(), Error> {
     writer.write_all(&MAGIC_BYTES)
       .context("failed to write pack")?;
     writer.write_i32::<LE>(self.mask)
       .context("failed to write pack")?;
     writer.write_u32::<LE>(self.pages.len() as u32)
       .context("failed to write pages_len for pack")?;
-    for page in self.pages.iter() {
-      page.write_v2(&mut writer)?;
+
+    let page_count = self.pages.len();
+    for (page_index, page) in self.pages.iter().enumerate() {
+      page.write_v2_with_progress(&mut writer, page_index, page_count, on_progress)?;
     };
 
     writer.flush()
@@ -281,6 +576,178 @@ impl Pack {
 
     Ok(())
   }
+
+  fn write_v3<W: Write>(&self, mut writer: W, codec: Codec) -> Result<(), Error> {
+    writer.write_all(&MAGIC_BYTES_V3)
+      .context("failed to write pack")?;
+    writer.write_u8(codec.tag())
+      .context("failed to write codec tag for pack")?;
+
+    let mut body = Cursor::new(Vec::new());
+    self.write_v3_body(&mut body)?;
+    let body = body.into_inner();
+
+    writer.write_u32::<LE>(body.len() as u32)
+      .context("failed to write decompressed length for pack")?;
+
+    match codec {
+      Codec::None => writer.write_all(&body)
+        .context("failed to write pack")?,
+      Codec::Zstd { level } => {
+        let mut encoder = zstd::stream::write::Encoder::new(&mut writer, level)
+          .context("failed to start zstd encoder")?;
+        encoder.write_all(&body)
+          .context("failed to write pack")?;
+        encoder.finish()
+          .context("failed to finish zstd encoder")?;
+      },
+      #[cfg(feature = "lzma")]
+      Codec::Lzma { level } => {
+        let mut encoder = xz2::write::XzEncoder::new(&mut writer, level);
+        encoder.write_all(&body)
+          .context("failed to write pack")?;
+        encoder.finish()
+          .context("failed to finish lzma encoder")?;
+      },
+      #[cfg(not(feature = "lzma"))]
+      Codec::Lzma { .. } => return Err(Error::CodecNotEnabled(codec, "lzma")),
+      #[cfg(feature = "bzip2")]
+      Codec::Bzip2 { level } => {
+        let mut encoder = bzip2::write::BzEncoder::new(&mut writer, bzip2::Compression::new(level));
+        encoder.write_all(&body)
+          .context("failed to write pack")?;
+        encoder.finish()
+          .context("failed to finish bzip2 encoder")?;
+      },
+      #[cfg(not(feature = "bzip2"))]
+      Codec::Bzip2 { .. } => return Err(Error::CodecNotEnabled(codec, "bzip2"))
+    };
+
+    writer.flush()
+      .context("failed to flush writer")?;
+
+    Ok(())
+  }
+
+  fn write_v2_body<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+    writer.write_i32::<LE>(self.mask)
+      .context("failed to write pack")?;
+    writer.write_u32::<LE>(self.pages.len() as u32)
+      .context("failed to write pages_len for pack")?;
+    for page in self.pages.iter() {
+      page.write_v2(&mut writer)?;
+    };
+
+    Ok(())
+  }
+
+  fn write_v3_body<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+    writer.write_i32::<LE>(self.mask)
+      .context("failed to write pack")?;
+    writer.write_u32::<LE>(self.pages.len() as u32)
+      .context("failed to write pages_len for pack")?;
+    for page in self.pages.iter() {
+      page.write_v3(&mut writer)?;
+    };
+
+    Ok(())
+  }
+}
+
+/// The page metadata of a pack file, read lazily by [`Pack::read_index`] without
+/// decoding any images.
+#[derive(Debug, Clone)]
+pub struct PackIndex {
+  pub mask: i32,
+  pub pages: Vec<PageHeader>
+}
+
+impl PackIndex {
+  fn read_body<R: Read + Seek>(mut reader: R) -> Result<Self, Error> {
+    let mask = reader.read_i32::<LE>()
+      .context("failed to read pack")?;
+    let pages_len = reader.read_u32::<LE>()
+      .context("failed to read pages_len for pack")?;
+    let pages = (0..pages_len)
+      .map(|_| PageHeader::read(&mut reader))
+      .collect::<Result<Vec<PageHeader>, Error>>()?;
+
+    Ok(PackIndex { mask, pages })
+  }
+
+  /// Like [`PackIndex::read_body`], but for uncompressed "V3" pack files, whose pages
+  /// each carry a trailing CRC32 checksum (see [`Page::read_v3`]) that must be skipped
+  /// over rather than mistaken for the next page's header.
+  fn read_body_v3<R: Read + Seek>(mut reader: R) -> Result<Self, Error> {
+    let mask = reader.read_i32::<LE>()
+      .context("failed to read pack")?;
+    let pages_len = reader.read_u32::<LE>()
+      .context("failed to read pages_len for pack")?;
+    let pages = (0..pages_len)
+      .map(|_| PageHeader::read_v3(&mut reader))
+      .collect::<Result<Vec<PageHeader>, Error>>()?;
+
+    Ok(PackIndex { mask, pages })
+  }
+}
+
+/// Metadata about a single [`Page`], as produced by [`Pack::read_index`].
+///
+/// `image_offset` and `image_len` describe the page's still-undecoded image blob,
+/// and can be passed to [`Pack::read_page_at`] to decode it on demand.
+#[derive(Debug, Clone)]
+pub struct PageHeader {
+  pub name: String,
+  pub mask: i32,
+  pub entries: Vec<Entry>,
+  pub width: u32,
+  pub height: u32,
+  pub image_offset: u64,
+  pub image_len: u32
+}
+
+impl PageHeader {
+  fn read<R: Read + Seek>(mut reader: R) -> Result<Self, Error> {
+    let name = read_string(&mut reader)
+      .context("failed to read name for page")?;
+    let entries_len = reader.read_u32::<LE>()
+      .context("failed to read entries_len for page")?;
+    let mask = reader.read_i32::<LE>()
+      .context("failed to read mask for page")?;
+    let entries = (0..entries_len)
+      .map(|_| Entry::read(&mut reader))
+      .collect::<Result<Vec<Entry>, Error>>()?;
+
+    let image_len = reader.read_u32::<LE>()
+      .context("failed to read image length for page")?;
+    let image_offset = reader.stream_position()
+      .context("failed to read pack")?;
+
+    let (width, height) = {
+      use image::ImageDecoder;
+      let decoder = image::codecs::png::PngDecoder::new(&mut reader)
+        .context("failed to read image header for page")?;
+      decoder.dimensions()
+    };
+
+    reader.seek(io::SeekFrom::Start(image_offset + u64::from(image_len)))
+      .context("failed to seek past page image")?;
+
+    Ok(PageHeader { name, mask, entries, width, height, image_offset, image_len })
+  }
+
+  /// Like [`PageHeader::read`], but for a "V3" page, which appends a trailing CRC32
+  /// checksum (see [`Page::write_v3`]) after the image that isn't part of the next
+  /// page's header and must be skipped over. The checksum itself isn't verified here,
+  /// since doing so would mean reading the whole image blob, defeating the purpose of
+  /// a lazy index.
+  fn read_v3<R: Read + Seek>(mut reader: R) -> Result<Self, Error> {
+    let header = PageHeader::read(&mut reader)?;
+    let _checksum = reader.read_u32::<LE>()
+      .context("failed to read checksum for page")?;
+
+    Ok(header)
+  }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -295,7 +762,17 @@ pub enum FormatVersion {
   /// - Images do not end with `0xDEADBEEF`, and instead have an `int32`/`uint32` prepended describing length in bytes.
   ///
   /// Packs will be saved with "V2" by default.
-  V2
+  V2,
+  /// "V3" is not a real format used anywhere else; it is this crate's own extension,
+  /// wrapping a "V2"-like page stream in an optional whole-file compression [`Codec`].
+  ///
+  /// The file is prefixed with four bytes, `PZP3`, followed by a 1-byte codec tag,
+  /// a `uint32` giving the decompressed length of the page stream, then a page stream
+  /// (mask, page count, and pages, with no `PZPK` prefix of its own) run through the
+  /// chosen codec. Each page is otherwise serialized exactly like "V2", but with a
+  /// trailing CRC32 checksum over its serialized body, letting readers detect a
+  /// truncated or corrupted page (see [`Error::ChecksumMismatch`]).
+  V3
 }
 
 impl Default for FormatVersion {
@@ -305,6 +782,74 @@ impl Default for FormatVersion {
   }
 }
 
+/// Compression codec applied to the page stream of a [`FormatVersion::V3`] pack file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+  /// The page stream is stored uncompressed.
+  None,
+  /// [zstd](https://github.com/facebook/zstd) compression, enabled by default.
+  Zstd {
+    /// Compression level, see [`zstd::stream::write::Encoder::new`] for valid ranges.
+    level: i32
+  },
+  /// LZMA compression, gated behind the `lzma` cargo feature.
+  Lzma {
+    /// Compression level, `0..=9`.
+    level: u32
+  },
+  /// bzip2 compression, gated behind the `bzip2` cargo feature.
+  Bzip2 {
+    /// Compression level, `1..=9`.
+    level: u32
+  }
+}
+
+impl Codec {
+  const TAG_NONE: u8 = 0;
+  const TAG_ZSTD: u8 = 1;
+  const TAG_LZMA: u8 = 2;
+  const TAG_BZIP2: u8 = 3;
+
+  fn tag(self) -> u8 {
+    match self {
+      Codec::None => Self::TAG_NONE,
+      Codec::Zstd { .. } => Self::TAG_ZSTD,
+      Codec::Lzma { .. } => Self::TAG_LZMA,
+      Codec::Bzip2 { .. } => Self::TAG_BZIP2
+    }
+  }
+}
+
+impl Default for Codec {
+  #[inline]
+  fn default() -> Self {
+    Codec::Zstd { level: zstd::DEFAULT_COMPRESSION_LEVEL }
+  }
+}
+
+/// The stage of a page's processing a [`ProgressEvent`] reports on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressStage {
+  /// The page's serialized bytes are being read from the pack file.
+  Reading,
+  /// The page's image bytes are being decoded into pixels.
+  Decoding,
+  /// The page's image is being encoded into its on-disk format.
+  Encoding,
+  /// The page's serialized bytes are being written to the pack file.
+  Writing
+}
+
+/// A progress notification emitted by [`Pack::read_with_progress`]/[`Pack::write_with_progress`]
+/// as each page is processed.
+#[derive(Debug, Clone)]
+pub struct ProgressEvent {
+  pub page_index: usize,
+  pub page_count: usize,
+  pub page_name: String,
+  pub stage: ProgressStage
+}
+
 fn write_string<W: Write>(mut writer: W, s: &str) -> io::Result<()> {
   writer.write_u32::<LE>(s.len() as u32)?;
   writer.write_all(s.as_bytes())?;
@@ -341,21 +886,142 @@ fn read_string<R: Read>(mut reader: R) -> io::Result<String> {
 
 fn read_buffer<R: Read>(mut reader: R) -> io::Result<Vec<u8>> {
   let len = reader.read_u32::<LE>()?;
+  read_buffer_of_len(reader, len)
+}
+
+fn read_buffer_of_len<R: Read>(reader: R, len: u32) -> io::Result<Vec<u8>> {
   let mut buf = Vec::with_capacity(len as usize);
   reader.take(len as u64).read_to_end(&mut buf)?;
   Ok(buf)
 }
 
-fn read_until_pattern<R: Read>(mut reader: R, pat: &[u8]) -> io::Result<Vec<u8>> {
-  let mut buf = Vec::new();
-  let len = loop {
-    if let Some(stripped) = buf.strip_suffix(pat) {
-      break stripped.len();
-    } else {
-      buf.push(reader.read_u8()?);
+/// A [`Read`] adapter that feeds every byte read through to a [`crc32fast::Hasher`],
+/// used to checksum a page's serialized body as it is parsed by [`Page::read_v3`].
+struct HashingReader<'a, R> {
+  inner: R,
+  hasher: &'a mut crc32fast::Hasher
+}
+
+impl<'a, R> HashingReader<'a, R> {
+  fn new(inner: R, hasher: &'a mut crc32fast::Hasher) -> Self {
+    HashingReader { inner, hasher }
+  }
+}
+
+impl<'a, R: Read> Read for HashingReader<'a, R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    self.hasher.update(&buf[..n]);
+    Ok(n)
+  }
+}
+
+/// Reads from `reader` until `pat` is found, returning everything read up to (but not
+/// including) the match; bytes after the match are left unconsumed in `reader`. Uses a
+/// Boyer-Moore-Horspool bad-character skip table to scan each buffered chunk, rather
+/// than re-checking the whole output on every single byte.
+///
+/// `R` must be [`BufRead`] (rather than pulling fixed-size chunks off a plain [`Read`])
+/// so that only the confirmed-matched prefix plus the terminator itself is consumed;
+/// otherwise any unread tail of the underlying buffer would be silently lost to callers
+/// reading more data from `reader` afterwards.
+fn read_until_pattern<R: BufRead>(mut reader: R, pat: &[u8]) -> io::Result<Vec<u8>> {
+  debug_assert!(!pat.is_empty());
+  let pat_len = pat.len();
+
+  // For each possible byte, how far to slide the pattern when it appears as the last
+  // byte of a failed match attempt; bytes not in `pat` (besides its last byte) default
+  // to sliding the whole pattern length past the mismatch.
+  let mut skip_table = [pat_len; 256];
+  for (i, &byte) in pat[..pat_len - 1].iter().enumerate() {
+    skip_table[byte as usize] = pat_len - 1 - i;
+  };
+
+  let mut out = Vec::new();
+  // Bytes already consumed from `reader` but not yet confirmed free of a match;
+  // carried over between fills so a terminator straddling a buffer boundary is still
+  // found. Always shorter than `pat_len`, since a match spanning only these bytes
+  // would already have been found on a previous iteration.
+  let mut leftover: Vec<u8> = Vec::new();
+
+  loop {
+    let buf = reader.fill_buf()?;
+    if buf.is_empty() {
+      return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "pattern not found before end of stream"));
+    };
+
+    let mut window = leftover.clone();
+    window.extend_from_slice(buf);
+
+    let mut pos = 0;
+    let mut found = None;
+    while pos + pat_len <= window.len() {
+      if &window[pos..pos + pat_len] == pat {
+        found = Some(pos);
+        break;
+      };
+
+      pos += skip_table[window[pos + pat_len - 1] as usize];
     };
+
+    if let Some(pos) = found {
+      out.extend_from_slice(&window[..pos]);
+      // Only consume the bytes of `buf` that fall within or before the match; any
+      // trailing bytes of `buf` stay buffered in `reader` for the next read.
+      let consumed = (pos + pat_len).saturating_sub(leftover.len());
+      reader.consume(consumed);
+      return Ok(out);
+    };
+
+    // Every position up to here has been confirmed not to start a match; flush it, but
+    // retain the last `pat_len - 1` bytes since they might still be a match's prefix.
+    let flush_len = window.len().saturating_sub(pat_len - 1);
+    out.extend_from_slice(&window[..flush_len]);
+    leftover = window.split_off(flush_len);
+    reader.consume(buf.len());
   };
+}
 
-  buf.truncate(len);
-  Ok(buf)
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn read_until_pattern_two_pages_leaves_second_page_intact() {
+    let mut data = Vec::new();
+    data.extend_from_slice(&[1, 2, 3, 4, 5]);
+    data.extend_from_slice(&END_OF_IMAGE);
+    data.extend_from_slice(&[6, 7, 8]);
+    data.extend_from_slice(&END_OF_IMAGE);
+
+    let mut reader = BufReader::new(Cursor::new(data));
+
+    let first = read_until_pattern(&mut reader, &END_OF_IMAGE).unwrap();
+    assert_eq!(first, vec![1, 2, 3, 4, 5]);
+
+    let second = read_until_pattern(&mut reader, &END_OF_IMAGE).unwrap();
+    assert_eq!(second, vec![6, 7, 8]);
+  }
+
+  #[test]
+  fn read_until_pattern_errors_on_missing_terminator() {
+    let mut reader = BufReader::new(Cursor::new(vec![1, 2, 3]));
+    let err = read_until_pattern(&mut reader, &END_OF_IMAGE).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+  }
+
+  #[test]
+  fn read_index_v3_reads_second_page_past_first_pages_checksum() {
+    let page = |name: &str| Page::new(name.to_owned(), Vec::new(), RgbaImage::new(2, 2));
+    let pack = Pack::new(vec![page("first"), page("second")]);
+
+    let mut buf = Cursor::new(Vec::new());
+    pack.write_with(&mut buf, FormatVersion::V3, Codec::None)
+      .unwrap();
+
+    let index = Pack::read_index(Cursor::new(buf.into_inner())).unwrap();
+    assert_eq!(index.pages.len(), 2);
+    assert_eq!(index.pages[0].name, "first");
+    assert_eq!(index.pages[1].name, "second");
+  }
 }